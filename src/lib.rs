@@ -1,9 +1,9 @@
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::{MediaSource, MediaSourceStream};
+use symphonia::core::meta::{MetadataLog, MetadataOptions, MetadataRevision, StandardTagKey};
 use symphonia::core::probe::Hint;
-use symphonia::core::audio::Signal;
+use symphonia::core::audio::SampleBuffer;
 use realfft::RealFftPlanner;
 use rustfft::num_complex::Complex32;
 use std::fs::File;
@@ -15,6 +15,26 @@ const MAX_BPM: f32 = 180.0;
 const MIN_FREQUENCY: f32 = 50.0;
 const MAX_FREQUENCY: f32 = 1000.0;
 const AUTOCORR_THRESHOLD: f32 = 0.05;
+const TEMPO_MAP_WINDOW_SECONDS: f32 = 10.0;
+const TEMPO_MAP_HOP_SECONDS: f32 = 5.0;
+
+/// How a multi-channel decoded buffer is reduced to one signal for the FFT.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelMode {
+    /// Average all channels together for each frame.
+    Mono,
+    /// Use a single channel index, ignoring the rest.
+    Channel(usize),
+}
+
+/// How the per-frame onset strength is derived from the FFT magnitude spectrum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnsetMethod {
+    /// Summed magnitude-squared energy in `[min_frequency, max_frequency]`.
+    BandEnergy,
+    /// Half-wave-rectified spectral flux: `sum_k max(0, |X_t[k]| - |X_{t-1}[k]|)`.
+    SpectralFlux,
+}
 
 pub struct BpmConfig {
     pub fft_size: usize,
@@ -24,6 +44,14 @@ pub struct BpmConfig {
     pub min_bpm: f32,
     pub max_bpm: f32,
     pub autocorr_threshold: f32,
+    pub channel_mode: ChannelMode,
+    /// When set, resample the decoded mono stream to this rate before framing.
+    pub target_sample_rate: Option<u32>,
+    pub onset_method: OnsetMethod,
+    /// Window length, in seconds, used by [`BpmDetector::detect_tempo_map`].
+    pub tempo_map_window_seconds: f32,
+    /// Hop, in seconds, between consecutive [`BpmDetector::detect_tempo_map`] windows.
+    pub tempo_map_hop_seconds: f32,
 }
 
 impl Default for BpmConfig {
@@ -36,10 +64,68 @@ impl Default for BpmConfig {
             min_bpm: MIN_BPM,
             max_bpm: MAX_BPM,
             autocorr_threshold: AUTOCORR_THRESHOLD,
+            channel_mode: ChannelMode::Mono,
+            target_sample_rate: None,
+            onset_method: OnsetMethod::BandEnergy,
+            tempo_map_window_seconds: TEMPO_MAP_WINDOW_SECONDS,
+            tempo_map_hop_seconds: TEMPO_MAP_HOP_SECONDS,
         }
     }
 }
 
+/// Linearly resamples a mono signal from `from_rate` to `to_rate`.
+fn resample_linear(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if samples.is_empty() || from_rate == to_rate {
+        return samples.to_vec();
+    }
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let out_len = ((samples.len() as f64) / ratio).round() as usize;
+    let last = samples.len() - 1;
+
+    (0..out_len)
+        .map(|i| {
+            let src_pos = i as f64 * ratio;
+            let idx = src_pos.floor() as usize;
+            let frac = (src_pos - idx as f64) as f32;
+            let a = samples[idx.min(last)];
+            let b = samples[(idx + 1).min(last)];
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// A single tempo hypothesis returned by [`BpmDetector::detect_candidates_from_samples`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BpmCandidate {
+    pub bpm: f32,
+    /// Peak autocorrelation magnitude, normalized against the lag-0 autocorrelation.
+    pub confidence: f32,
+    /// `true` if a stronger candidate exists at ~2x, 3x, 1/2x, or 1/3x this BPM.
+    pub related_to_octave: bool,
+}
+
+/// A local tempo estimate over one window of [`BpmDetector::detect_tempo_map`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoSegment {
+    pub start_seconds: f32,
+    pub end_seconds: f32,
+    pub bpm: f32,
+    pub confidence: f32,
+}
+
+/// BPM paired with the decoded track's container/codec metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BpmAnalysis {
+    pub bpm: f32,
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub duration_seconds: f32,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum BpmError {
     #[error("File not found: {0}")]
@@ -52,6 +138,14 @@ pub enum BpmError {
     NoValidBpm { min: f32, max: f32 },
 }
 
+/// The opened Symphonia pipeline for one track, returned by [`BpmDetector::probe_track`].
+struct ProbedTrack {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    sample_rate: u32,
+    metadata_log: MetadataLog,
+}
+
 pub struct BpmDetector {
     config: BpmConfig,
 }
@@ -77,112 +171,387 @@ impl Default for BpmDetector {
 impl BpmDetector {
     pub fn detect_from_file(&self, path: &str) -> Result<f32, BpmError> {
         let file = File::open(path).map_err(|_| BpmError::FileNotFound(path.to_string()))?;
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let extension_hint = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str());
+
+        self.detect_from_reader(Box::new(file), extension_hint)
+    }
+
+    /// Like [`Self::detect_from_file`], but reads from any Symphonia [`MediaSource`].
+    /// `extension_hint` (e.g. `"flac"`) helps the format probe without a file path.
+    pub fn detect_from_reader(
+        &self,
+        source: Box<dyn MediaSource>,
+        extension_hint: Option<&str>,
+    ) -> Result<f32, BpmError> {
+        let (energies, sample_rate) = self.analyze_envelope(source, extension_hint)?;
+        self.detect_from_samples(&energies, sample_rate)
+    }
+
+    /// Like [`Self::detect_from_file`], but returns the ranked tempo candidates of
+    /// [`Self::detect_candidates_from_samples`] instead of collapsing to one BPM.
+    pub fn detect_candidates_from_file(&self, path: &str) -> Result<Vec<BpmCandidate>, BpmError> {
+        let file = File::open(path).map_err(|_| BpmError::FileNotFound(path.to_string()))?;
+        let extension_hint = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str());
+
+        self.detect_candidates_from_reader(Box::new(file), extension_hint)
+    }
+
+    /// Like [`Self::detect_from_reader`], but returns the ranked tempo candidates of
+    /// [`Self::detect_candidates_from_samples`] instead of collapsing to one BPM.
+    pub fn detect_candidates_from_reader(
+        &self,
+        source: Box<dyn MediaSource>,
+        extension_hint: Option<&str>,
+    ) -> Result<Vec<BpmCandidate>, BpmError> {
+        let (energies, sample_rate) = self.analyze_envelope(source, extension_hint)?;
+        self.detect_candidates_from_samples(&energies, sample_rate)
+    }
+
+    /// Slides a `tempo_map_window_seconds`-long, `tempo_map_hop_seconds`-spaced
+    /// window across the onset envelope and runs the autocorrelation per window,
+    /// returning a tempo per segment instead of one whole-file BPM.
+    pub fn detect_tempo_map(&self, path: &str) -> Result<Vec<TempoSegment>, BpmError> {
+        let file = File::open(path).map_err(|_| BpmError::FileNotFound(path.to_string()))?;
+        let extension_hint = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str());
+
+        let (energies, sample_rate) = self.analyze_envelope(Box::new(file), extension_hint)?;
+
+        let seconds_per_frame = self.config.hop_size as f32 / sample_rate as f32;
+        let window_frames = (self.config.tempo_map_window_seconds / seconds_per_frame).round() as usize;
+        let hop_frames = ((self.config.tempo_map_hop_seconds / seconds_per_frame).round() as usize).max(1);
+
+        let mut segments = vec![];
+        let mut pos = 0;
+        while pos + window_frames <= energies.len() {
+            let window = &energies[pos..pos + window_frames];
+            if let Ok(candidates) = self.detect_candidates_from_samples(window, sample_rate) {
+                let best = candidates
+                    .iter()
+                    .filter(|c| !c.related_to_octave)
+                    .max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())
+                    .or_else(|| {
+                        candidates
+                            .iter()
+                            .max_by(|a, b| a.confidence.partial_cmp(&b.confidence).unwrap())
+                    });
+                if let Some(best) = best {
+                    segments.push(TempoSegment {
+                        start_seconds: pos as f32 * seconds_per_frame,
+                        end_seconds: (pos + window_frames) as f32 * seconds_per_frame,
+                        bpm: best.bpm,
+                        confidence: best.confidence,
+                    });
+                }
+            }
+
+            pos += hop_frames;
+        }
+
+        if segments.is_empty() {
+            return Err(BpmError::InsufficientData);
+        }
+
+        Ok(segments)
+    }
+
+    /// Like [`Self::detect_from_file`], but also returns the sample rate, channels,
+    /// duration, and any title/artist/album tags the Symphonia probe extracted.
+    pub fn detect_with_metadata(&self, path: &str) -> Result<BpmAnalysis, BpmError> {
+        let file = File::open(path).map_err(|_| BpmError::FileNotFound(path.to_string()))?;
+        let extension_hint = std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str());
+
+        let ProbedTrack {
+            mut format,
+            mut decoder,
+            sample_rate,
+            mut metadata_log,
+        } = Self::probe_track(Box::new(file), extension_hint)?;
+
+        let channels = format
+            .default_track()
+            .and_then(|track| track.codec_params.channels)
+            .map(|channels| channels.count())
+            .unwrap_or(1);
+
+        let (title, artist, album) = {
+            let revision = format.metadata().current().or_else(|| metadata_log.current());
+            Self::extract_tags(revision)
+        };
+
+        let mono = Self::decode_mono_samples(&mut *format, &mut *decoder, self.config.channel_mode)?;
+        let duration_seconds = mono.len() as f32 / sample_rate as f32;
+
+        let (resampled, analysis_sample_rate) = match self.config.target_sample_rate {
+            Some(target) if target != sample_rate => {
+                (resample_linear(&mono, sample_rate, target), target)
+            }
+            _ => (mono, sample_rate),
+        };
+
+        let energies = self.frame_energies(&resampled, analysis_sample_rate)?;
+        let bpm = self.detect_from_samples(&energies, analysis_sample_rate)?;
+
+        Ok(BpmAnalysis {
+            bpm,
+            sample_rate,
+            channels,
+            duration_seconds,
+            title,
+            artist,
+            album,
+        })
+    }
+
+    fn extract_tags(
+        revision: Option<&MetadataRevision>,
+    ) -> (Option<String>, Option<String>, Option<String>) {
+        let mut title = None;
+        let mut artist = None;
+        let mut album = None;
+
+        if let Some(revision) = revision {
+            for tag in revision.tags() {
+                let value = tag.value.to_string();
+                match tag.std_key {
+                    Some(StandardTagKey::TrackTitle) => title = Some(value),
+                    Some(StandardTagKey::Artist) => artist = Some(value),
+                    Some(StandardTagKey::Album) => album = Some(value),
+                    _ => {}
+                }
+            }
+        }
+
+        (title, artist, album)
+    }
+
+    fn analyze_envelope(
+        &self,
+        source: Box<dyn MediaSource>,
+        extension_hint: Option<&str>,
+    ) -> Result<(Vec<f32>, u32), BpmError> {
+        let ProbedTrack {
+            mut format,
+            mut decoder,
+            sample_rate,
+            ..
+        } = Self::probe_track(source, extension_hint)?;
+
+        let mono = Self::decode_mono_samples(&mut *format, &mut *decoder, self.config.channel_mode)?;
+
+        let (mono, sample_rate) = match self.config.target_sample_rate {
+            Some(target) if target != sample_rate => {
+                (resample_linear(&mono, sample_rate, target), target)
+            }
+            _ => (mono, sample_rate),
+        };
+
+        let energies = self.frame_energies(&mono, sample_rate)?;
+
+        Ok((energies, sample_rate))
+    }
+
+    /// Probes `source`, opens its default track's decoder, and reads the sample rate.
+    /// Shared by [`Self::analyze_envelope`] and [`Self::detect_with_metadata`].
+    fn probe_track(
+        source: Box<dyn MediaSource>,
+        extension_hint: Option<&str>,
+    ) -> Result<ProbedTrack, BpmError> {
+        let mss = MediaSourceStream::new(source, Default::default());
 
         // Configurar hint con la extensión del archivo para mejor detección de formato
         let mut hint = Hint::new();
-        if let Some(extension) = std::path::Path::new(path).extension() {
-            if let Some(ext_str) = extension.to_str() {
-                hint.with_extension(ext_str);
-            }
+        if let Some(ext_str) = extension_hint {
+            hint.with_extension(ext_str);
         }
 
         let probed = symphonia::default::get_probe()
             .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
             .map_err(|_| BpmError::UnsupportedFormat)?;
 
-        let mut format = probed.format;
+        let format = probed.format;
+        let metadata_log = probed.metadata;
         let track = format.default_track().ok_or(BpmError::UnsupportedFormat)?;
-        let mut decoder = symphonia::default::get_codecs()
+        let decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &DecoderOptions::default())
             .map_err(|_| BpmError::UnsupportedFormat)?;
 
         let sample_rate = track.codec_params.sample_rate.ok_or(BpmError::UnsupportedFormat)?;
+
+        Ok(ProbedTrack {
+            format,
+            decoder,
+            sample_rate,
+            metadata_log,
+        })
+    }
+
+    // `SampleBuffer::<f32>` uses Symphonia's `FromSample` conversions internally, so
+    // every decoded variant (U8/U16/U24/U32/S8/S16/S24/S32/F32/F64) lands here as f32
+    // without us having to match each one by hand.
+    fn decode_mono_samples(
+        format: &mut dyn symphonia::core::formats::FormatReader,
+        decoder: &mut dyn symphonia::core::codecs::Decoder,
+        channel_mode: ChannelMode,
+    ) -> Result<Vec<f32>, BpmError> {
+        let mut sample_buf: Option<SampleBuffer<f32>> = None;
+        let mut mono = vec![];
+
+        while let Ok(packet) = format.next_packet() {
+            let decoded = decoder.decode(&packet).map_err(|_| BpmError::UnsupportedFormat)?;
+
+            let spec = *decoded.spec();
+            let duration = decoded.capacity() as u64;
+            let buf = sample_buf.get_or_insert_with(|| SampleBuffer::<f32>::new(duration, spec));
+            buf.copy_interleaved_ref(decoded);
+
+            let channels = spec.channels.count();
+            for samples in buf.samples().chunks_exact(channels) {
+                let sample = match channel_mode {
+                    ChannelMode::Mono => samples.iter().sum::<f32>() / channels as f32,
+                    ChannelMode::Channel(idx) => samples[idx.min(channels - 1)],
+                };
+                mono.push(sample);
+            }
+        }
+
+        Ok(mono)
+    }
+
+    fn frame_energies(&self, mono: &[f32], sample_rate: u32) -> Result<Vec<f32>, BpmError> {
         let mut planner = RealFftPlanner::<f32>::new();
         let r2c = planner.plan_fft_forward(self.config.fft_size);
         let mut input = r2c.make_input_vec();
         let mut spectrum = r2c.make_output_vec();
 
-        let mut frame = vec![];
-        let mut energies = vec![];
+        let bin_freq = sample_rate as f32 / self.config.fft_size as f32;
+        let low_bin = (self.config.min_frequency / bin_freq).round() as usize;
+        let high_bin = ((self.config.max_frequency / bin_freq).round() as usize).min(spectrum.len());
+        let low_bin = low_bin.min(high_bin);
 
-        while let Ok(packet) = format.next_packet() {
-            let decoded = decoder.decode(&packet).map_err(|_| BpmError::UnsupportedFormat)?;
-            match decoded {
-                symphonia::core::audio::AudioBufferRef::F32(buf) => {
-                    for frame_idx in 0..buf.frames() {
-                        let sample = buf.chan(0)[frame_idx];
-                        frame.push(sample);
-                        if frame.len() >= self.config.fft_size {
-                            input.copy_from_slice(&frame[..self.config.fft_size]);
-                            r2c.process(&mut input, &mut spectrum).map_err(|_| BpmError::UnsupportedFormat)?;
-
-                            let bin_freq = sample_rate as f32 / self.config.fft_size as f32;
-                            let low_bin = (self.config.min_frequency / bin_freq).round() as usize;
-                            let high_bin = (self.config.max_frequency / bin_freq).round() as usize;
-
-                            let energy: f32 = spectrum[low_bin..high_bin]
-                                .iter()
-                                .map(|c: &Complex32| c.norm_sqr())
-                                .sum();
-
-                            energies.push(energy);
-                            frame.drain(..self.config.hop_size);
-                        }
+        // Previous frame's magnitudes for the spectral-flux onset method; starts at
+        // zero, so the first frame's flux is its whole in-band magnitude sum.
+        let mut prev_magnitudes = vec![0.0_f32; high_bin - low_bin];
+
+        let mut energies = vec![];
+        let mut pos = 0;
+        while pos + self.config.fft_size <= mono.len() {
+            input.copy_from_slice(&mono[pos..pos + self.config.fft_size]);
+            r2c.process(&mut input, &mut spectrum).map_err(|_| BpmError::UnsupportedFormat)?;
+
+            let onset = match self.config.onset_method {
+                OnsetMethod::BandEnergy => spectrum[low_bin..high_bin]
+                    .iter()
+                    .map(|c: &Complex32| c.norm_sqr())
+                    .sum(),
+                OnsetMethod::SpectralFlux => {
+                    let mut flux = 0.0_f32;
+                    for (magnitude, prev) in spectrum[low_bin..high_bin]
+                        .iter()
+                        .map(|c: &Complex32| c.norm())
+                        .zip(prev_magnitudes.iter_mut())
+                    {
+                        flux += (magnitude - *prev).max(0.0);
+                        *prev = magnitude;
                     }
+                    flux
                 }
-                symphonia::core::audio::AudioBufferRef::S16(buf) => {
-                    for frame_idx in 0..buf.frames() {
-                        let sample = buf.chan(0)[frame_idx] as f32 / i16::MAX as f32;
-                        frame.push(sample);
-                        if frame.len() >= self.config.fft_size {
-                            input.copy_from_slice(&frame[..self.config.fft_size]);
-                            r2c.process(&mut input, &mut spectrum).map_err(|_| BpmError::UnsupportedFormat)?;
-
-                            let bin_freq = sample_rate as f32 / self.config.fft_size as f32;
-                            let low_bin = (self.config.min_frequency / bin_freq).round() as usize;
-                            let high_bin = (self.config.max_frequency / bin_freq).round() as usize;
-
-                            let energy: f32 = spectrum[low_bin..high_bin]
-                                .iter()
-                                .map(|c: &Complex32| c.norm_sqr())
-                                .sum();
-
-                            energies.push(energy);
-                            frame.drain(..self.config.hop_size);
-                        }
-                    }
+            };
+
+            energies.push(onset);
+            pos += self.config.hop_size;
+        }
+
+        Ok(energies)
+    }
+
+    pub fn detect_from_samples(&self, energies: &[f32], sample_rate: u32) -> Result<f32, BpmError> {
+        let candidates = self.detect_candidates_from_samples(energies, sample_rate)?;
+
+        // Preferir el candidato más alto si su confianza es similar a la del primero.
+        let best = &candidates[0];
+        let best_bpm = if candidates.len() >= 2 {
+            let second = &candidates[1];
+            if (best.confidence - second.confidence).abs() / best.confidence < 0.1
+                && second.bpm > best.bpm
+            {
+                second.bpm
+            } else {
+                best.bpm
+            }
+        } else {
+            best.bpm
+        };
+
+        Ok((best_bpm * 2.0).round() / 2.0)
+    }
+
+    /// Like [`Self::detect_from_samples`], but returns the in-range tempos of the five
+    /// strongest autocorrelation peaks ranked by confidence, with explicit tempo-octave
+    /// grouping, instead of collapsing to one BPM.
+    pub fn detect_candidates_from_samples(
+        &self,
+        energies: &[f32],
+        sample_rate: u32,
+    ) -> Result<Vec<BpmCandidate>, BpmError> {
+        let (peaks, seconds_per_frame, lag0_autocorr) = self.autocorrelation_peaks(energies, sample_rate)?;
+
+        let mut candidates: Vec<BpmCandidate> = vec![];
+        for (lag, magnitude) in peaks.iter().take(5) {
+            let interval = *lag as f32 * seconds_per_frame;
+            let bpm = self.config.min_bpm / interval;
+            if bpm >= self.config.min_bpm && bpm <= self.config.max_bpm {
+                let confidence = if lag0_autocorr > 0.0 { magnitude / lag0_autocorr } else { 0.0 };
+                candidates.push(BpmCandidate {
+                    bpm,
+                    confidence,
+                    related_to_octave: false,
+                });
+            }
+        }
+
+        if candidates.is_empty() {
+            return Err(BpmError::NoValidBpm {
+                min: self.config.min_bpm,
+                max: self.config.max_bpm,
+            });
+        }
+
+        // A candidate is an octave error if a stronger candidate sits at ~2x/3x/half/third
+        // its BPM; flag it so callers can pick the right tempo octave themselves.
+        const OCTAVE_RATIOS: [f32; 4] = [2.0, 3.0, 0.5, 1.0 / 3.0];
+        for i in 0..candidates.len() {
+            for j in 0..candidates.len() {
+                if i == j || candidates[j].confidence <= candidates[i].confidence {
+                    continue;
                 }
-                symphonia::core::audio::AudioBufferRef::U8(buf) => {
-                    for frame_idx in 0..buf.frames() {
-                        let sample = (buf.chan(0)[frame_idx] as f32 - 128.0) / 128.0;
-                        frame.push(sample);
-                        if frame.len() >= self.config.fft_size {
-                            input.copy_from_slice(&frame[..self.config.fft_size]);
-                            r2c.process(&mut input, &mut spectrum).map_err(|_| BpmError::UnsupportedFormat)?;
-
-                            let bin_freq = sample_rate as f32 / self.config.fft_size as f32;
-                            let low_bin = (self.config.min_frequency / bin_freq).round() as usize;
-                            let high_bin = (self.config.max_frequency / bin_freq).round() as usize;
-
-                            let energy: f32 = spectrum[low_bin..high_bin]
-                                .iter()
-                                .map(|c: &Complex32| c.norm_sqr())
-                                .sum();
-
-                            energies.push(energy);
-                            frame.drain(..self.config.hop_size);
-                        }
-                    }
+                let ratio = candidates[i].bpm / candidates[j].bpm;
+                if OCTAVE_RATIOS.iter().any(|r| (ratio - r).abs() < 0.05) {
+                    candidates[i].related_to_octave = true;
+                    break;
                 }
-                _ => continue,
             }
         }
 
-        self.detect_from_samples(&energies, sample_rate)
+        Ok(candidates)
     }
 
-    pub fn detect_from_samples(&self, energies: &[f32], sample_rate: u32) -> Result<f32, BpmError> {
+    /// Normalizes `energies`, runs the autocorrelation used to estimate tempo, and
+    /// returns its local maxima as `(lag, magnitude)` pairs sorted by descending
+    /// magnitude, alongside `seconds_per_frame` and the lag-0 autocorrelation (used
+    /// to normalize confidence).
+    fn autocorrelation_peaks(
+        &self,
+        energies: &[f32],
+        sample_rate: u32,
+    ) -> Result<(Vec<(usize, f32)>, f32, f32), BpmError> {
         if energies.len() < 3 {
             return Err(BpmError::InsufficientData);
         }
@@ -212,6 +581,9 @@ impl BpmDetector {
             }
         }
 
+        let lag0_autocorr =
+            normalized.iter().map(|v| v * v).sum::<f32>() / normalized.len() as f32;
+
         // Encontrar picos
         let mut peaks = vec![];
         for i in 1..autocorr.len() - 1 {
@@ -224,36 +596,6 @@ impl BpmDetector {
 
         peaks.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
 
-        // Convertir a BPM
-        let mut candidates = vec![];
-        for (lag, magnitude) in peaks.iter().take(5) {
-            let interval = *lag as f32 * seconds_per_frame;
-            let bpm = self.config.min_bpm / interval;
-            if bpm >= self.config.min_bpm && bpm <= self.config.max_bpm {
-                candidates.push((bpm, *magnitude));
-            }
-        }
-
-        if candidates.is_empty() {
-            return Err(BpmError::NoValidBpm {
-                min: self.config.min_bpm,
-                max: self.config.max_bpm
-            });
-        }
-
-        // Seleccionar mejor BPM (preferir más alto si magnitudes similares)
-        let (bpm1, mag1) = candidates[0];
-        let (best_bpm, _) = if candidates.len() >= 2 {
-            let (bpm2, mag2) = candidates[1];
-            if (mag1 - mag2).abs() / mag1 < 0.1 && bpm2 > bpm1 {
-                (bpm2, mag2)
-            } else {
-                (bpm1, mag1)
-            }
-        } else {
-            (bpm1, mag1)
-        };
-
-        Ok((best_bpm * 2.0).round() / 2.0)
+        Ok((peaks, seconds_per_frame, lag0_autocorr))
     }
 }